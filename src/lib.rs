@@ -25,6 +25,7 @@
 
 use rayon::prelude::*;
 use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::{Read, Write};
 use std::ptr;
@@ -34,6 +35,52 @@ use rust_htslib::bgzf;
 use rust_htslib::bgzf::CompressionLevel as CompLvl;
 use rust_htslib::tpool::ThreadPool;
 
+/// What to do with a record whose trimmed length falls below
+/// `min_length`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShortReadPolicy {
+    /// Omit the record entirely.
+    Drop,
+    /// Replace the trimmed read with a single placeholder base, so
+    /// paired output files stay mate-synchronized.
+    Placeholder,
+}
+
+/// In paired mode, how the per-mate short-read outcome combines into
+/// a decision for the pair as a whole. Only consulted when
+/// `ShortReadPolicy::Drop` is in effect; `Placeholder` never drops a
+/// record, so pairing is preserved regardless.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairFilter {
+    /// Drop the pair if either mate is too short.
+    Either,
+    /// Drop the pair only if both mates are too short.
+    Both,
+}
+
+/// Which end of the read an adaptor is expected to contaminate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdaptorEnd {
+    Five,
+    Three,
+}
+
+/// A single adaptor sequence to search for, together with which end
+/// of the read it is expected on. `min_frac` overrides the run's
+/// global match fraction for this adaptor alone, when set.
+#[derive(Clone, Debug)]
+pub struct Adaptor {
+    pub seq: Vec<u8>,
+    pub end: AdaptorEnd,
+    pub min_frac: Option<f64>,
+}
+
+impl Adaptor {
+    fn frac(&self, default_min_frac: f64) -> f64 {
+        self.min_frac.unwrap_or(default_min_frac)
+    }
+}
+
 /// Just the naive algorithm for string matching with bounded
 /// mismatches.
 fn naive_matching(adaptor: &[u8], read: &[u8], min_frac: f64, min_ltrs: usize) -> usize {
@@ -78,6 +125,177 @@ fn naive_matching(adaptor: &[u8], read: &[u8], min_frac: f64, min_ltrs: usize) -
     m
 }
 
+/// Indel-aware alternative to `naive_matching`: semi-global alignment
+/// of the adaptor against the read allowing substitutions,
+/// insertions and deletions. Unit-cost edit distance is computed over
+/// `adaptor[0..n]` and `read[0..m]` with `D[0][j] = 0` for all `j`
+/// (the adaptor may begin at any read position) and `D[i][0] = i`. An
+/// origin matrix records, for each cell, the read column at which its
+/// best alignment path began, so the adaptor start position can be
+/// read back out. Same contract as `naive_matching`: returns the
+/// leftmost qualifying read position, or `read.len()` if no match
+/// clears the threshold.
+fn indel_matching(adaptor: &[u8], read: &[u8], min_frac: f64, min_ltrs: usize) -> usize {
+    let n = adaptor.len();
+    let m = read.len();
+    let d_delta = 1f64 - min_frac;
+
+    if n == 0 {
+        return 0;
+    }
+
+    let mut d = vec![vec![0usize; m + 1]; n + 1];
+    let mut origin = vec![vec![0usize; m + 1]; n + 1];
+    for (j, o) in origin[0].iter_mut().enumerate() {
+        *o = j;
+    }
+    for (i, row) in d.iter_mut().enumerate().take(n + 1).skip(1) {
+        row[0] = i;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let sub = (
+                d[i - 1][j - 1] + usize::from(adaptor[i - 1] != read[j - 1]),
+                origin[i - 1][j - 1],
+            );
+            let del = (d[i - 1][j] + 1, origin[i - 1][j]);
+            let ins = (d[i][j - 1] + 1, origin[i][j - 1]);
+            let (cost, from) = [sub, del, ins].into_iter().min_by_key(|&(c, _)| c).unwrap();
+            d[i][j] = cost;
+            origin[i][j] = from;
+        }
+    }
+
+    let mut best_start = m;
+
+    // the adaptor matches in full somewhere inside the read
+    for j in 0..=m {
+        let start = origin[n][j];
+        if j - start >= min_ltrs && d[n][j] as f64 <= n as f64 * d_delta {
+            best_start = min(best_start, start);
+        }
+    }
+
+    // the adaptor runs off the 3' end of the read: only its first i
+    // letters are actually covered, giving a partial overlap
+    for i in min_ltrs..=n {
+        let start = origin[i][m];
+        if d[i][m] as f64 <= i as f64 * d_delta {
+            best_start = min(best_start, start);
+        }
+    }
+
+    best_start
+}
+
+/// Like `naive_matching`, but for a 5' adaptor: its start position
+/// isn't free (it sits directly at the read's start), so this only
+/// decides how far it extends into the read. Returns the read
+/// position where the adaptor ends and the kept sequence begins, or
+/// `0` if no acceptable match is found.
+fn naive_matching_5prime(adaptor: &[u8], read: &[u8], min_frac: f64, min_ltrs: usize) -> usize {
+    let len = min(adaptor.len(), read.len());
+    if len < min_ltrs {
+        return 0;
+    }
+    let d_delta = 1f64 - min_frac;
+    let mut d: usize = 0;
+    let mut best = 0usize;
+    for j in 0..len {
+        if read[j] != adaptor[j] {
+            d += 1;
+        }
+        let matched = j + 1;
+        if matched >= min_ltrs && d as f64 <= matched as f64 * d_delta {
+            best = matched;
+        }
+    }
+    best
+}
+
+/// Indel-aware counterpart to `naive_matching_5prime`: semi-global
+/// alignment of the adaptor against the read, anchored so that only
+/// `D[0][0] = 0` (the adaptor cannot float, unlike `indel_matching`'s
+/// free leading gap), with a free trailing gap in the read so the
+/// adaptor may end before the read does, or run off the read's own
+/// end if it is shorter than the adaptor.
+fn indel_matching_5prime(adaptor: &[u8], read: &[u8], min_frac: f64, min_ltrs: usize) -> usize {
+    let n = adaptor.len();
+    let m = read.len();
+    if n == 0 {
+        return 0;
+    }
+    const INF: usize = usize::MAX / 4;
+    let d_delta = 1f64 - min_frac;
+
+    let mut d = vec![vec![INF; m + 1]; n + 1];
+    d[0][0] = 0;
+    for (i, row) in d.iter_mut().enumerate().take(n + 1).skip(1) {
+        row[0] = i;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let sub = d[i - 1][j - 1] + usize::from(adaptor[i - 1] != read[j - 1]);
+            let del = d[i - 1][j] + 1;
+            let ins = d[i][j - 1] + 1;
+            d[i][j] = min(sub, min(del, ins));
+        }
+    }
+
+    let mut best = 0usize;
+
+    // the adaptor matches in full, ending somewhere inside the read
+    for j in min_ltrs..=m {
+        if d[n][j] as f64 <= n as f64 * d_delta {
+            best = max(best, j);
+        }
+    }
+    // the adaptor runs off the read's own end, i.e. the read is
+    // shorter than the adaptor
+    for i in min_ltrs..=n {
+        if d[i][m] as f64 <= i as f64 * d_delta {
+            best = max(best, m);
+        }
+    }
+
+    best
+}
+
+/// Mismatch tolerance used when scanning for a terminal poly-X run;
+/// not currently exposed as a command-line option.
+const POLY_X_MIN_FRAC: f64 = 0.9;
+
+/// Scan inward from the end of `read`, looking for a terminal run of
+/// a single repeated `base` (poly-G on two-color instruments, or any
+/// other base via `--poly-x`), tolerating a small mismatch fraction
+/// within the run. If a qualifying run of at least `min_run` bases is
+/// found, its start position is returned so the caller can pull
+/// `stop` back to it; otherwise `read.len()` is returned unchanged.
+fn trim_poly_x(read: &[u8], base: u8, min_frac: f64, min_run: usize) -> usize {
+    let stop = read.len();
+    let d_delta = 1f64 - min_frac;
+    let mut mismatches = 0usize;
+    let mut best_start = stop;
+    let mut i = stop;
+    while i > 0 {
+        i -= 1;
+        if read[i] != base {
+            mismatches += 1;
+        }
+        let len = stop - i;
+        if mismatches as f64 <= len as f64 * d_delta {
+            best_start = i;
+        } else {
+            break;
+        }
+    }
+    if stop - best_start >= min_run {
+        best_start
+    } else {
+        stop
+    }
+}
+
 /// Find the positions in the read of the first non-N and last non-N.
 fn trim_n_ends(read: &[u8]) -> (usize, usize) {
     (
@@ -139,6 +357,25 @@ fn qual_trim(qual: &[u8], cut_front: i32, cut_back: i32) -> (usize, usize) {
     (start as usize, stop as usize)
 }
 
+/// Fill `buf[*filled..]` by reading repeatedly until the buffer is
+/// full or a genuine EOF (a zero-length read) is reached.
+/// `Read::read` is permitted to return fewer bytes than requested
+/// without being at EOF, so a single call cannot tell the two apart.
+fn fill_buffer<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> Result<(), Box<dyn Error>> {
+    while *filled < buf.len() {
+        let n = reader.read(&mut buf[*filled..])?;
+        if n == 0 {
+            break;
+        }
+        *filled += n;
+    }
+    Ok(())
+}
+
 fn shift(buf: &mut [u8], cursor: &mut usize, filled: &mut usize) {
     let mut j = 0;
     for i in *cursor..*filled {
@@ -174,46 +411,217 @@ impl std::fmt::Display for FQRec {
     }
 }
 
+/// Per-record trimming statistics, filled in by `FQRec::trim_bounds`
+/// and `FQRec::process` and folded into a `TrimReport`. Kept separate
+/// from `TrimReport` itself because a fresh one is needed per record,
+/// while the report accumulates across an entire run.
+#[derive(Default)]
+struct RecordStats {
+    length_before: usize,
+    length_after: usize,
+    qual_bases_removed: usize,
+    n_bases_removed: usize,
+    adaptor_bases_removed: usize,
+    poly_x_bases_removed: usize,
+    // only set in paired mode, by the insert-size/read-through check
+    // in `process_reads_paired`; not part of `trim_bounds`
+    read_through_bases_removed: usize,
+    // parallel to the `adaptors` slice passed to `trim_bounds`
+    adaptor_hits: Vec<bool>,
+}
+
+impl RecordStats {
+    fn new(n_adaptors: usize) -> Self {
+        RecordStats {
+            adaptor_hits: vec![false; n_adaptors],
+            ..Default::default()
+        }
+    }
+}
+
+/// Machine-readable summary of a trimming run: how many reads were
+/// processed, how many bases were removed and by which stage, length
+/// histograms before and after trimming, and per-adaptor match
+/// counts (parallel to the `adaptors` slice the run was given). The
+/// five `*_bases_removed` fields are a strict partition: they always
+/// sum to the total bases removed across all processed reads (the
+/// difference between `length_hist_before` and `length_hist_after`).
+#[derive(Default, Debug)]
+pub struct TrimReport {
+    pub total_reads: u64,
+    pub reads_with_adaptor: u64,
+    pub qual_bases_removed: u64,
+    pub n_bases_removed: u64,
+    pub adaptor_bases_removed: u64,
+    pub poly_x_bases_removed: u64,
+    pub read_through_bases_removed: u64,
+    pub length_hist_before: HashMap<usize, u64>,
+    pub length_hist_after: HashMap<usize, u64>,
+    pub adaptor_matches: Vec<u64>,
+}
+
+impl TrimReport {
+    fn new(n_adaptors: usize) -> Self {
+        TrimReport {
+            adaptor_matches: vec![0; n_adaptors],
+            ..Default::default()
+        }
+    }
+
+    fn add_record(&mut self, stats: &RecordStats) {
+        self.total_reads += 1;
+        if stats.adaptor_hits.iter().any(|&hit| hit) {
+            self.reads_with_adaptor += 1;
+        }
+        self.qual_bases_removed += stats.qual_bases_removed as u64;
+        self.n_bases_removed += stats.n_bases_removed as u64;
+        self.adaptor_bases_removed += stats.adaptor_bases_removed as u64;
+        self.poly_x_bases_removed += stats.poly_x_bases_removed as u64;
+        self.read_through_bases_removed += stats.read_through_bases_removed as u64;
+        *self.length_hist_before.entry(stats.length_before).or_insert(0) += 1;
+        *self.length_hist_after.entry(stats.length_after).or_insert(0) += 1;
+        for (count, &hit) in self.adaptor_matches.iter_mut().zip(stats.adaptor_hits.iter()) {
+            if hit {
+                *count += 1;
+            }
+        }
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.total_reads += other.total_reads;
+        self.reads_with_adaptor += other.reads_with_adaptor;
+        self.qual_bases_removed += other.qual_bases_removed;
+        self.n_bases_removed += other.n_bases_removed;
+        self.adaptor_bases_removed += other.adaptor_bases_removed;
+        self.poly_x_bases_removed += other.poly_x_bases_removed;
+        self.read_through_bases_removed += other.read_through_bases_removed;
+        for (len, count) in other.length_hist_before {
+            *self.length_hist_before.entry(len).or_insert(0) += count;
+        }
+        for (len, count) in other.length_hist_after {
+            *self.length_hist_after.entry(len).or_insert(0) += count;
+        }
+        for (a, b) in self.adaptor_matches.iter_mut().zip(other.adaptor_matches.iter()) {
+            *a += b;
+        }
+        self
+    }
+}
+
 impl FQRec {
-    fn process(
-        &mut self,
-        adaptor: &[u8],
-        cutoff: u8,
-        min_frac: f64,
-        min_ltrs: usize,
-        buf: &Vec<u8>,
-    ) {
-        let seqlen = if self.r < self.o {
+    fn seqlen(&self) -> usize {
+        if self.r < self.o {
             self.o - self.r - 1
         } else {
             0
-        };
+        }
+    }
+
+    /// Work out the `[start, stop)` interval of the read that should
+    /// be kept, trimming N bases from both ends, low quality bases
+    /// from the 3' end only, and any of `adaptors` from whichever end
+    /// they are declared on.
+    /// Does not touch the buffer; see `compress` for that. Tallies
+    /// what was trimmed and why into `stats`, whose `adaptor_hits`
+    /// must already be sized to `adaptors.len()`: each stage credits
+    /// its own exact contribution to narrowing `[start, stop)`, so
+    /// the tallies are a strict partition of `length_before -
+    /// length_after` (`process_reads_paired` separately credits any
+    /// further insert-size/read-through narrowing it applies on top).
+    fn trim_bounds(
+        &self,
+        adaptors: &[Adaptor],
+        cutoff: u8,
+        min_frac: f64,
+        min_ltrs: usize,
+        indel: bool,
+        poly_x: Option<(u8, usize)>,
+        buf: &[u8],
+        stats: &mut RecordStats,
+    ) -> (usize, usize) {
+        let seqlen = self.seqlen();
+        stats.length_before = seqlen;
         let (qstart, qstop) = qual_trim(&buf[self.q..self.q + seqlen], 0, cutoff as i32);
         // consecutive N values at both ends
         let (nstart, nstop) = trim_n_ends(&buf[self.r..self.r + seqlen]);
-        // so no N or low qual bases can interfere with adaptor
+
+        // quality and N trim both propose a 3' cutoff; the tighter
+        // one wins and is credited with the bases it removed
         let mut stop = min(qstop, nstop);
+        if qstop <= nstop {
+            stats.qual_bases_removed += seqlen - stop;
+        } else {
+            stats.n_bases_removed += seqlen - stop;
+        }
 
-        // find the adaptor at the 3' end
-        let adaptor_start =
-            naive_matching(adaptor, &buf[self.r..self.r + stop], min_frac, min_ltrs);
+        // try each 3' adaptor against the same pre-trim window and
+        // take the leftmost accepted match across all of them;
+        // matching against a window already narrowed by an earlier
+        // adaptor would make the result depend on adaptor order
+        let window_stop = stop;
+        for (i, a) in adaptors.iter().enumerate().filter(|(_, a)| a.end == AdaptorEnd::Three) {
+            let frac = a.frac(min_frac);
+            let adaptor_start = if indel {
+                indel_matching(&a.seq, &buf[self.r..self.r + window_stop], frac, min_ltrs)
+            } else {
+                naive_matching(&a.seq, &buf[self.r..self.r + window_stop], frac, min_ltrs)
+            };
+            if adaptor_start < window_stop {
+                stats.adaptor_hits[i] = true;
+            }
+            stop = min(stop, adaptor_start);
+        }
+        stats.adaptor_bases_removed += window_stop - stop;
 
-        stop = min(stop, adaptor_start);
+        // adaptor trimming can expose a fresh run of N at the new 3' end
+        let stop_before_n_retrim = stop;
         let (_, nstop) = trim_n_ends(&buf[self.r..self.r + stop]);
         stop = min(stop, nstop);
-        let start = min(max(qstart, nstart), stop);
+        stats.n_bases_removed += stop_before_n_retrim - stop;
+
+        // two-color chemistry can leave a high-quality poly-G (or
+        // other base) run that neither quality nor adaptor trimming
+        // would otherwise catch
+        if let Some((base, min_run)) = poly_x {
+            let stop_before_poly_x = stop;
+            stop = trim_poly_x(&buf[self.r..self.r + stop], base, POLY_X_MIN_FRAC, min_run);
+            stats.poly_x_bases_removed += stop_before_poly_x - stop;
+        }
 
-        /* ADS: Removing the comments in the next two lines breaks up
-         * this function, which would allow the work to be done in two
-         * loops, but that would mean waiting for slower threads. */
+        let mut start = min(max(qstart, nstart), stop);
+        if qstart >= nstart {
+            stats.qual_bases_removed += start;
+        } else {
+            stats.n_bases_removed += start;
+        }
 
-        // }
-        // fn compress(&mut self, buf: &Vec<u8>) {
+        // try each 5' adaptor, taking the furthest accepted extent
+        for (i, a) in adaptors.iter().enumerate().filter(|(_, a)| a.end == AdaptorEnd::Five) {
+            let frac = a.frac(min_frac);
+            let extent = if indel {
+                indel_matching_5prime(&a.seq, &buf[self.r + start..self.r + stop], frac, min_ltrs)
+            } else {
+                naive_matching_5prime(&a.seq, &buf[self.r + start..self.r + stop], frac, min_ltrs)
+            };
+            let new_start = min(stop, start + extent);
+            if new_start > start {
+                stats.adaptor_bases_removed += new_start - start;
+                stats.adaptor_hits[i] = true;
+            }
+            start = new_start;
+        }
 
-        /* ADS: below here, the instance variables other than n and e
-         * become invalidated
-         */
+        stats.length_after = stop - start;
+        (start, stop)
+    }
 
+    /// Rewrite the record in place, in 4-line FASTQ format, keeping
+    /// only the bases and quality scores in `[start, stop)`.
+    ///
+    /// Precondition: `self.n`..`self.o` and `self.q`..`self.q +
+    /// seqlen()` are valid for this record. Postcondition: `self.n`
+    /// and `self.e` are valid; the other fields are invalidated.
+    fn compress(&mut self, start: usize, stop: usize, buf: &Vec<u8>) {
         let b = buf.as_ptr() as *mut u8;
         let r_sz = stop - start;
 
@@ -258,13 +666,90 @@ impl FQRec {
         self.e = cursor;
         // postcondition of this function: self.n and self.e are valid
     }
+
+    /// Rewrite the record in place as a single placeholder base (`N`
+    /// with minimal quality), used in place of `compress` when the
+    /// trimmed read is too short to keep but the record's slot in the
+    /// output still needs to be filled (e.g. to preserve mate order).
+    fn compress_placeholder(&mut self, buf: &Vec<u8>) {
+        let b = buf.as_ptr() as *mut u8;
+        let mut cursor = self.n
+            + match &buf[self.n..self.r].iter().position(|&x| x == b' ') {
+                Some(x) => x,
+                // no space in the header: fall back to the position of
+                // the line's own trailing newline, not self.r itself,
+                // which is already an absolute offset
+                _ => &(self.r - self.n - 1),
+            };
+        unsafe {
+            *b.add(cursor) = b'\n';
+        }
+        cursor += 1;
+        unsafe {
+            *b.add(cursor) = b'N';
+        }
+        cursor += 1;
+        unsafe {
+            *b.add(cursor) = b'\n';
+        }
+        cursor += 1;
+        unsafe {
+            *b.add(cursor) = b'+';
+        }
+        cursor += 1;
+        unsafe {
+            *b.add(cursor) = b'\n';
+        }
+        cursor += 1;
+        unsafe {
+            *b.add(cursor) = b'#'; // minimal phred quality
+        }
+        cursor += 1;
+        unsafe {
+            *b.add(cursor) = b'\n';
+        }
+        cursor += 1;
+        self.e = cursor;
+    }
+
+    /// Single-end processing: trim, then either compress in place or
+    /// apply the short-read policy. Returns `false` when the record
+    /// should be dropped from the output entirely.
+    fn process(
+        &mut self,
+        adaptors: &[Adaptor],
+        cutoff: u8,
+        min_frac: f64,
+        min_ltrs: usize,
+        indel: bool,
+        poly_x: Option<(u8, usize)>,
+        min_length: usize,
+        policy: ShortReadPolicy,
+        buf: &Vec<u8>,
+        stats: &mut RecordStats,
+    ) -> bool {
+        let (start, stop) =
+            self.trim_bounds(adaptors, cutoff, min_frac, min_ltrs, indel, poly_x, buf, stats);
+        if stop - start < min_length {
+            match policy {
+                ShortReadPolicy::Drop => return false,
+                ShortReadPolicy::Placeholder => {
+                    self.compress_placeholder(buf);
+                    return true;
+                }
+            }
+        }
+        self.compress(start, stop, buf);
+        true
+    }
+
     fn write<W: Write>(&self, buf: &Vec<u8>, writer: &mut W) {
         writer.write(&buf[self.n..self.e]).unwrap();
     }
 }
 
 #[inline(always)]
-fn next_line(buf: &mut [u8], filled: usize, offset: usize) -> usize {
+fn next_line(buf: &[u8], filled: usize, offset: usize) -> usize {
     for i in offset..filled {
         if buf[i] == b'\n' {
             return i + 1;
@@ -273,83 +758,151 @@ fn next_line(buf: &mut [u8], filled: usize, offset: usize) -> usize {
     usize::MAX
 }
 
+/// Parse the next four-line record starting at `cursor`, without
+/// advancing it. Returns a `FQRec` with `e == usize::MAX` when
+/// `buf[cursor..filled]` doesn't yet hold a complete record (the
+/// caller should refill the buffer and retry). Deliberately leaves
+/// committing the cursor to the caller, rather than doing it here:
+/// in paired mode two of these calls (one per mate) must both
+/// succeed before either cursor may move, or a mate whose record
+/// parses first can have its bytes discarded out from under it while
+/// the other is still incomplete, desynchronizing the pair stream. A
+/// complete record that doesn't look like FASTQ (missing `@`/`+`
+/// markers, or a sequence/quality length mismatch) is reported as an
+/// `Err` naming the byte offset, rather than silently producing
+/// garbage.
 #[inline(always)]
-fn get_next_record(buf: &mut [u8], cursor: &mut usize, filled: usize) -> FQRec {
-    // ADS: here is where we should detect malformed records
-    let n = *cursor;
+fn get_next_record(buf: &[u8], cursor: usize, filled: usize) -> Result<FQRec, Box<dyn Error>> {
+    let n = cursor;
     let r = next_line(buf, filled, n);
     let o = next_line(buf, filled, r);
     let q = next_line(buf, filled, o);
     let e = next_line(buf, filled, q);
-    if e != usize::MAX {
-        *cursor = e;
-        debug_assert!(buf[n] == b'@');
+    if e == usize::MAX {
+        return Ok(FQRec { n, r, o, q, e });
+    }
+
+    if buf[n] != b'@' {
+        return Err(format!("expected '@' starting FASTQ record at byte offset {n}").into());
+    }
+    if buf[o] != b'+' {
+        return Err(format!("expected '+' separator at byte offset {o}").into());
+    }
+    let seq_len = o - r - 1;
+    let qual_len = e - q - 1;
+    if seq_len != qual_len {
+        return Err(format!(
+            "sequence and quality length mismatch ({seq_len} vs {qual_len}) \
+             in record starting at byte offset {n}"
+        )
+        .into());
     }
-    FQRec { n, r, o, q, e }
+
+    Ok(FQRec { n, r, o, q, e })
 }
 
 fn process_reads<R: Read, W: Write>(
     buffer_size: usize,
-    adaptor: &[u8],
+    adaptors: &[Adaptor],
     reader: &mut R,
     mut writer: &mut W,
     cutoff: u8,
     min_frac: f64,
     min_ltrs: usize,
-) -> Result<(), Box<dyn Error>> {
+    indel: bool,
+    poly_x: Option<(u8, usize)>,
+    min_length: usize,
+    policy: ShortReadPolicy,
+) -> Result<TrimReport, Box<dyn Error>> {
     let mut buf: Vec<u8> = vec![b'\0'; buffer_size];
     let mut filled = 0usize;
     let mut cursor = 0usize;
 
     let mut recs: Vec<FQRec> = Vec::new();
+    let mut keep: Vec<bool> = Vec::new();
+    let mut report = TrimReport::new(adaptors.len());
 
     loop {
         // move any unused data to start of buffer
         shift(&mut buf, &mut cursor, &mut filled);
 
         // read the input to fill the buffer
-        filled += reader.read(&mut buf[filled..])?;
+        fill_buffer(reader, &mut buf, &mut filled)?;
 
         // find the sequenced read records
         recs.clear(); // keep capacity
         loop {
-            let fq = get_next_record(&mut buf, &mut cursor, filled);
+            let fq = get_next_record(&buf, cursor, filled)?;
             if fq.e == usize::MAX {
                 break;
             }
+            cursor = fq.e;
             recs.push(fq);
         }
 
-        // find end-points of trimmed reads
-        recs.par_iter_mut()
-            .for_each(|fq_rec| fq_rec.process(&adaptor, cutoff, min_frac, min_ltrs, &buf));
+        // find end-points of trimmed reads, folding per-record stats
+        // into a report across threads as we go
+        keep.clear();
+        keep.resize(recs.len(), true);
+        let chunk_report = recs
+            .par_iter_mut()
+            .zip(keep.par_iter_mut())
+            .fold(
+                || TrimReport::new(adaptors.len()),
+                |mut acc, (fq_rec, k)| {
+                    let mut stats = RecordStats::new(adaptors.len());
+                    *k = fq_rec.process(
+                        adaptors, cutoff, min_frac, min_ltrs, indel, poly_x, min_length, policy,
+                        &buf, &mut stats,
+                    );
+                    acc.add_record(&stats);
+                    acc
+                },
+            )
+            .reduce(|| TrimReport::new(adaptors.len()), TrimReport::merge);
+        report = report.merge(chunk_report);
 
         /* ADS: could do separately: make record a contiguous chunk */
         // recs.iter_mut().for_each(|x| x.compress(&buf));
 
+        // drop records that fell below min_length under Drop policy
+        let mut it = keep.iter();
+        recs.retain(|_| *it.next().unwrap());
+
         // write all records to output file
         recs.iter_mut().for_each(|x| x.write(&mut buf, &mut writer));
 
-        // exit if previous read hit end of file
+        // exit if previous read hit end of file; leftover bytes at this
+        // point are a record truncated mid-parse, not a clean EOF
         if filled < buf.len() {
+            if cursor < filled {
+                return Err(format!(
+                    "truncated FASTQ record at byte offset {cursor} (unexpected end of input)"
+                )
+                .into());
+            }
             break;
         }
     }
 
-    Ok(())
+    Ok(report)
 }
 
 pub fn remove_adaptors(
     zip: bool,
     n_threads: u32,
     buf_sz: usize,
-    adaptor: &[u8],
+    adaptors: &[Adaptor],
     input: &String,
     output: &String,
     cutoff: u8,
     min_frac: f64,
     min_ltrs: usize,
-) -> Result<(), Box<dyn Error>> {
+    indel: bool,
+    poly_x: Option<(u8, usize)>,
+    min_length: usize,
+    policy: ShortReadPolicy,
+) -> Result<TrimReport, Box<dyn Error>> {
     let lvl = match zip {
         true => CompLvl::Default,
         false => CompLvl::NoCompression,
@@ -364,11 +917,390 @@ pub fn remove_adaptors(
     }
     process_reads(
         buf_sz,
-        adaptor,
+        adaptors,
         &mut reader,
         &mut writer,
         cutoff,
         min_frac,
         min_ltrs,
+        indel,
+        poly_x,
+        min_length,
+        policy,
     )
 }
+
+/// Reverse complement of a nucleotide sequence. Anything other than
+/// `ACGT` is mapped to `N`.
+fn revcomp(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|&b| match b {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// For an overlapping (short-insert) read pair, find the fragment
+/// length by aligning `read1` against the reverse complement of
+/// `read2`. An insert of length `l` fills the first `l` bases of
+/// `read1` and the last `l` bases of that reverse complement, with
+/// each read's adaptor beyond it, so the longest such overlap whose
+/// mismatch fraction clears `min_frac` is taken as the insert size.
+/// Returns `None` when the reads agree over their whole
+/// shared length, since that means the fragment is at least as long
+/// as the reads and there is no adaptor read-through to trim.
+///
+/// `min_overlap` is deliberately a separate parameter from the
+/// adaptor matcher's `min_ltrs`: this is a whole-pair statistical
+/// call, not an adaptor search, and needs enough bases that a
+/// coincidental match can't be mistaken for a real short insert. A
+/// `min_ltrs`-sized (as low as 1) overlap would accept a single
+/// matching base between unrelated, non-overlapping mates as "the"
+/// insert size and truncate both reads to nothing.
+fn find_insert_size(read1: &[u8], read2: &[u8], min_frac: f64, min_overlap: usize) -> Option<usize> {
+    let rc2 = revcomp(read2);
+    let m = min(read1.len(), rc2.len());
+    if m < min_overlap {
+        return None;
+    }
+    let d_delta = 1f64 - min_frac;
+    let mut best: Option<usize> = None;
+    for l in min_overlap..=m {
+        let d = read1[..l]
+            .iter()
+            .zip(&rc2[rc2.len() - l..])
+            .filter(|(a, b)| a != b)
+            .count();
+        if d as f64 <= l as f64 * d_delta {
+            best = Some(l);
+        }
+    }
+    best.filter(|&l| l < m)
+}
+
+fn process_reads_paired<R: Read, W: Write>(
+    buffer_size: usize,
+    adaptors: &[Adaptor],
+    reader1: &mut R,
+    reader2: &mut R,
+    mut writer1: &mut W,
+    mut writer2: &mut W,
+    cutoff: u8,
+    min_frac: f64,
+    min_ltrs: usize,
+    indel: bool,
+    poly_x: Option<(u8, usize)>,
+    min_length: usize,
+    policy: ShortReadPolicy,
+    pair_filter: PairFilter,
+    pair_min_overlap: usize,
+) -> Result<TrimReport, Box<dyn Error>> {
+    let mut buf1: Vec<u8> = vec![b'\0'; buffer_size];
+    let mut buf2: Vec<u8> = vec![b'\0'; buffer_size];
+    let mut filled1 = 0usize;
+    let mut filled2 = 0usize;
+    let mut cursor1 = 0usize;
+    let mut cursor2 = 0usize;
+
+    let mut recs1: Vec<FQRec> = Vec::new();
+    let mut recs2: Vec<FQRec> = Vec::new();
+    let mut keep: Vec<bool> = Vec::new();
+    let mut report = TrimReport::new(adaptors.len());
+
+    loop {
+        // move any unused data to start of each buffer
+        shift(&mut buf1, &mut cursor1, &mut filled1);
+        shift(&mut buf2, &mut cursor2, &mut filled2);
+
+        // read both inputs to fill their buffers
+        fill_buffer(reader1, &mut buf1, &mut filled1)?;
+        fill_buffer(reader2, &mut buf2, &mut filled2)?;
+
+        // find the sequenced read records, in lockstep so mate order
+        // between the two files is preserved
+        recs1.clear();
+        recs2.clear();
+        loop {
+            let fq1 = get_next_record(&buf1, cursor1, filled1)?;
+            let fq2 = get_next_record(&buf2, cursor2, filled2)?;
+            if fq1.e == usize::MAX || fq2.e == usize::MAX {
+                break;
+            }
+            cursor1 = fq1.e;
+            cursor2 = fq2.e;
+            recs1.push(fq1);
+            recs2.push(fq2);
+        }
+
+        // find end-points of trimmed reads, then pull in the overlap
+        // between mates before committing either one to the buffer,
+        // folding per-record stats into a report across threads
+        keep.clear();
+        keep.resize(recs1.len(), true);
+        let chunk_report = recs1
+            .par_iter_mut()
+            .zip(recs2.par_iter_mut())
+            .zip(keep.par_iter_mut())
+            .fold(
+                || TrimReport::new(adaptors.len()),
+                |mut acc, ((fq1, fq2), k)| {
+                    let mut stats1 = RecordStats::new(adaptors.len());
+                    let mut stats2 = RecordStats::new(adaptors.len());
+                    let (start1, mut stop1) = fq1.trim_bounds(
+                        adaptors, cutoff, min_frac, min_ltrs, indel, poly_x, &buf1, &mut stats1,
+                    );
+                    let (start2, mut stop2) = fq2.trim_bounds(
+                        adaptors, cutoff, min_frac, min_ltrs, indel, poly_x, &buf2, &mut stats2,
+                    );
+
+                    if let Some(insert) = find_insert_size(
+                        &buf1[fq1.r..fq1.r + stop1],
+                        &buf2[fq2.r..fq2.r + stop2],
+                        min_frac,
+                        pair_min_overlap,
+                    ) {
+                        // `insert` is bounded only by stop1/stop2, not by
+                        // the 5' trim offsets, so clamp to start1/start2
+                        // to avoid stop landing before start, and credit
+                        // only the bases the clamped stop removes
+                        let new_stop1 = min(stop1, insert).max(start1);
+                        let new_stop2 = min(stop2, insert).max(start2);
+                        stats1.read_through_bases_removed += stop1 - new_stop1;
+                        stats2.read_through_bases_removed += stop2 - new_stop2;
+                        stop1 = new_stop1;
+                        stop2 = new_stop2;
+                    }
+                    stats1.length_after = stop1 - start1;
+                    stats2.length_after = stop2 - start2;
+                    acc.add_record(&stats1);
+                    acc.add_record(&stats2);
+
+                    let short1 = stop1 - start1 < min_length;
+                    let short2 = stop2 - start2 < min_length;
+                    let drop_pair = policy == ShortReadPolicy::Drop
+                        && match pair_filter {
+                            PairFilter::Either => short1 || short2,
+                            PairFilter::Both => short1 && short2,
+                        };
+                    *k = !drop_pair;
+                    if !drop_pair {
+                        if policy == ShortReadPolicy::Placeholder && short1 {
+                            fq1.compress_placeholder(&buf1);
+                        } else {
+                            fq1.compress(start1, stop1, &buf1);
+                        }
+                        if policy == ShortReadPolicy::Placeholder && short2 {
+                            fq2.compress_placeholder(&buf2);
+                        } else {
+                            fq2.compress(start2, stop2, &buf2);
+                        }
+                    }
+                    acc
+                },
+            )
+            .reduce(|| TrimReport::new(adaptors.len()), TrimReport::merge);
+        report = report.merge(chunk_report);
+
+        // drop pairs that fell below min_length under Drop policy
+        let mut it = keep.iter();
+        recs1.retain(|_| *it.next().unwrap());
+        let mut it = keep.iter();
+        recs2.retain(|_| *it.next().unwrap());
+
+        // write all record pairs to their respective output files, in
+        // the order they were read
+        recs1.iter_mut().for_each(|x| x.write(&mut buf1, &mut writer1));
+        recs2.iter_mut().for_each(|x| x.write(&mut buf2, &mut writer2));
+
+        // leftover bytes on a side that's done, which don't form a
+        // complete record, mean a record truncated mid-parse
+        if filled1 < buf1.len()
+            && cursor1 < filled1
+            && get_next_record(&buf1, cursor1, filled1)?.e == usize::MAX
+        {
+            return Err(format!(
+                "truncated FASTQ record in read 1 at byte offset {cursor1} (unexpected end of input)"
+            )
+            .into());
+        }
+        if filled2 < buf2.len()
+            && cursor2 < filled2
+            && get_next_record(&buf2, cursor2, filled2)?.e == usize::MAX
+        {
+            return Err(format!(
+                "truncated FASTQ record in read 2 at byte offset {cursor2} (unexpected end of input)"
+            )
+            .into());
+        }
+        // one side used up while the other still holds records
+        if (filled1 < buf1.len() && cursor1 == filled1 && cursor2 < filled2)
+            || (filled2 < buf2.len() && cursor2 == filled2 && cursor1 < filled1)
+        {
+            return Err("read 1 and read 2 have different numbers of records".into());
+        }
+        // exit once both inputs have hit end of file
+        if filled1 < buf1.len() && filled2 < buf2.len() {
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
+pub fn remove_adaptors_paired(
+    zip: bool,
+    n_threads: u32,
+    buf_sz: usize,
+    adaptors: &[Adaptor],
+    input1: &String,
+    input2: &String,
+    output1: &String,
+    output2: &String,
+    cutoff: u8,
+    min_frac: f64,
+    min_ltrs: usize,
+    indel: bool,
+    poly_x: Option<(u8, usize)>,
+    min_length: usize,
+    policy: ShortReadPolicy,
+    pair_filter: PairFilter,
+    pair_min_overlap: usize,
+) -> Result<TrimReport, Box<dyn Error>> {
+    let lvl = match zip {
+        true => CompLvl::Default,
+        false => CompLvl::NoCompression,
+    };
+    let mut reader1 = bgzf::Reader::from_path(input1)?;
+    let mut reader2 = bgzf::Reader::from_path(input2)?;
+    let mut writer1 = bgzf::Writer::from_path_with_level(output1, lvl)?;
+    let mut writer2 = bgzf::Writer::from_path_with_level(output2, lvl)?;
+
+    let tpool = ThreadPool::new(n_threads - 1)?;
+    if n_threads > 1 {
+        reader1.set_thread_pool(&tpool)?;
+        reader2.set_thread_pool(&tpool)?;
+        writer1.set_thread_pool(&tpool)?;
+        writer2.set_thread_pool(&tpool)?;
+    }
+    process_reads_paired(
+        buf_sz,
+        adaptors,
+        &mut reader1,
+        &mut reader2,
+        &mut writer1,
+        &mut writer2,
+        cutoff,
+        min_frac,
+        min_ltrs,
+        indel,
+        poly_x,
+        min_length,
+        policy,
+        pair_filter,
+        pair_min_overlap,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_placeholder_handles_header_without_space() {
+        let buf: Vec<u8> = b"@r1\nACGT\n+\nIIII\n".to_vec();
+        let mut rec = FQRec { n: 0, r: 4, o: 9, q: 11, e: 16 };
+        rec.compress_placeholder(&buf);
+        assert_eq!(&buf[..rec.e], b"@r1\nN\n+\n#\n");
+    }
+
+    #[test]
+    fn indel_matching_finds_exact_match_with_leading_bases() {
+        let adaptor = b"AGATCGGAAGAGC";
+        let mut read = b"TTTTT".to_vec();
+        read.extend_from_slice(adaptor);
+        assert_eq!(indel_matching(adaptor, &read, 1.0, adaptor.len()), 5);
+    }
+
+    #[test]
+    fn indel_matching_returns_read_len_when_no_match() {
+        let adaptor = b"AGATCGGAAGAGC";
+        let read = b"TTTTTTTTTTTTTTTTTT";
+        assert_eq!(
+            indel_matching(adaptor, read, 1.0, adaptor.len()),
+            read.len()
+        );
+    }
+
+    #[test]
+    fn indel_matching_empty_adaptor_matches_at_zero() {
+        assert_eq!(indel_matching(b"", b"ACGT", 1.0, 0), 0);
+    }
+
+    #[test]
+    fn indel_matching_tolerates_a_deletion_in_the_read() {
+        let adaptor = b"AGATCGGAAGAGC";
+        // adaptor with its middle base dropped, as if the read skipped it
+        let mut read = b"TTTTT".to_vec();
+        read.extend_from_slice(b"AGATCGAAGAGC");
+        assert_eq!(indel_matching(adaptor, &read, 0.9, 10), 5);
+    }
+
+    #[test]
+    fn indel_matching_5prime_stops_at_adaptor_end() {
+        let adaptor = b"AGATCGGAAGAGC";
+        let mut read = adaptor.to_vec();
+        read.extend_from_slice(b"ACGTACGTAC");
+        assert_eq!(
+            indel_matching_5prime(adaptor, &read, 1.0, adaptor.len()),
+            adaptor.len()
+        );
+    }
+
+    #[test]
+    fn indel_matching_5prime_runs_off_short_read() {
+        let adaptor = b"AGATCGGAAGAGC";
+        let read = &adaptor[0..5];
+        assert_eq!(indel_matching_5prime(adaptor, read, 1.0, 1), read.len());
+    }
+
+    #[test]
+    fn indel_matching_5prime_returns_zero_when_no_match() {
+        let adaptor = b"AAAA";
+        let read = b"CCCCCCCC";
+        assert_eq!(indel_matching_5prime(adaptor, read, 1.0, 1), 0);
+    }
+
+    #[test]
+    fn indel_matching_5prime_tolerates_an_insertion_in_the_read() {
+        let adaptor = b"AGATCGGAAGAGC";
+        // adaptor with an extra base inserted after its 6th letter
+        let read = b"AGATCGXGAAGAGC";
+        assert_eq!(indel_matching_5prime(adaptor, read, 0.9, 5), 14);
+    }
+
+    #[test]
+    fn find_insert_size_detects_read_through() {
+        let read1 = b"ACGTACGGGG";
+        let read2 = b"GTACGTAAAA";
+        assert_eq!(find_insert_size(read1, read2, 0.9, 4), Some(6));
+    }
+
+    #[test]
+    fn find_insert_size_none_when_reads_agree_throughout() {
+        let read1 = b"ACGT";
+        let read2 = b"ACGT";
+        assert_eq!(find_insert_size(read1, read2, 0.9, 1), None);
+    }
+
+    #[test]
+    fn find_insert_size_none_below_min_overlap() {
+        let read1 = b"AC";
+        let read2 = b"AC";
+        assert_eq!(find_insert_size(read1, read2, 0.9, 10), None);
+    }
+}
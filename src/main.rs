@@ -23,13 +23,14 @@
  * SOFTWARE.
  */
 
+use adapto_rs::{Adaptor, AdaptorEnd, PairFilter, ShortReadPolicy};
 use clap::Parser;
 use clap_num::number_range;
 use file_format::FileFormat as FFmt;
 use indoc;
 use num_cpus;
 use std::error::Error;
-use std::str::from_utf8;
+use std::fs;
 
 fn thread_range(s: &str) -> Result<u32, String> {
     number_range(s, 1, 255)
@@ -91,9 +92,17 @@ struct Args {
     #[arg(short, long, default_value_t = 20)]
     qual_cutoff: u8,
 
-    /// Adaptor sequence
+    /// 3' adaptor sequence (can be given more than once)
     #[arg(short, long, default_value = "AGATCGGAAGAGC")]
-    adaptor: Option<String>,
+    adaptor: Vec<String>,
+
+    /// File of additional 3' adaptor sequences, one per line
+    #[arg(long)]
+    adaptor_file: Option<String>,
+
+    /// 5' adaptor sequence (can be given more than once)
+    #[arg(long)]
+    adaptor5: Vec<String>,
 
     /// Proportion matching
     #[arg(short = 'r', long = "frac", default_value_t = 0.9)]
@@ -109,6 +118,50 @@ struct Args {
     #[arg(short, long, default_value_t = true)]
     keep_prefix: bool,
 
+    /// Allow insertions and deletions when matching the adaptor, at
+    /// the cost of a slower, DP-based matcher
+    #[arg(long)]
+    indel: bool,
+
+    /// Minimum length of a trimmed read to keep
+    #[arg(long, default_value_t = 0)]
+    min_length: usize,
+
+    /// What to do with a trimmed read shorter than min-length
+    #[arg(long, value_enum, default_value = "drop")]
+    on_short: ShortReadPolicy,
+
+    /// In paired mode, when to drop a pair for falling below min-length
+    #[arg(long, value_enum, default_value = "either")]
+    pair_filter: PairFilter,
+
+    /// Minimum overlap required between mates before a whole-pair
+    /// insert size (and any adaptor read-through) is accepted; kept
+    /// separate from --min-overlap since this is a statistical call
+    /// over the full read, not an adaptor search, and a small value
+    /// risks truncating unrelated, non-overlapping pairs
+    #[arg(long, default_value_t = 20)]
+    #[arg(value_parser = overlap_range)]
+    pair_min_overlap: usize,
+
+    /// Trim a terminal poly-G run (two-color chemistry); shorthand
+    /// for --poly-x G
+    #[arg(long)]
+    poly_g: bool,
+
+    /// Trim a terminal run of this repeated base
+    #[arg(long, value_name = "BASE")]
+    poly_x: Option<char>,
+
+    /// Minimum length of a poly-G/poly-X run to trim
+    #[arg(long, default_value_t = 5)]
+    poly_min_len: usize,
+
+    /// Write a JSON trimming report to this path, or to stderr if
+    /// given as "-"
+    #[arg(long, value_name = "PATH")]
+    report: Option<String>,
+
     /// Zip output files as BGZF format
     #[arg(short, long)]
     zip: bool,
@@ -127,6 +180,18 @@ struct Args {
     verbose: bool,
 }
 
+/// Read one adaptor sequence per line from `filename`, skipping blank
+/// lines, FASTA headers, and comment lines starting with '#'.
+fn read_adaptor_file(filename: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let contents = fs::read_to_string(filename)?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('>') && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
 fn is_readable(filename: &String) -> bool {
     use std::fs::File;
     let mut f = match File::open(&filename) {
@@ -145,7 +210,30 @@ fn main() -> Result<(), Box<dyn Error>> {
         return Err("buffer size must be positive")?;
     }
 
-    let adaptor = args.adaptor.unwrap().into_bytes();
+    let mut adaptor_seqs = args.adaptor.clone();
+    if let Some(ref adaptor_file) = args.adaptor_file {
+        adaptor_seqs.extend(read_adaptor_file(adaptor_file)?);
+    }
+    let adaptors: Vec<Adaptor> = adaptor_seqs
+        .iter()
+        .map(|s| Adaptor {
+            seq: s.clone().into_bytes(),
+            end: AdaptorEnd::Three,
+            min_frac: None,
+        })
+        .chain(args.adaptor5.iter().map(|s| Adaptor {
+            seq: s.clone().into_bytes(),
+            end: AdaptorEnd::Five,
+            min_frac: None,
+        }))
+        .collect();
+
+    let poly_x: Option<(u8, usize)> = if args.poly_g {
+        Some((b'G', args.poly_min_len))
+    } else {
+        args.poly_x
+            .map(|c| (c.to_ascii_uppercase() as u8, args.poly_min_len))
+    };
 
     if !is_readable(&args.fastq) {
         return Err(format!("file not readable: {}", args.fastq))?;
@@ -171,10 +259,17 @@ fn main() -> Result<(), Box<dyn Error>> {
             (None, None) => (),
         }
         eprintln!("quality cutoff: {}", args.qual_cutoff);
-        eprintln!("adaptor: {}", from_utf8(&adaptor)?);
+        eprintln!("3' adaptors: {}", adaptor_seqs.join(", "));
+        eprintln!("5' adaptors: {}", args.adaptor5.join(", "));
         eprintln!("overlap needed: {}", args.min_overlap);
         eprintln!("match needed: {}", args.min_match_frac);
         eprintln!("keep prefix: {}", args.keep_prefix);
+        eprintln!("allow indels: {}", args.indel);
+        eprintln!("minimum length: {}", args.min_length);
+        eprintln!("short read policy: {:?}", args.on_short);
+        eprintln!("pair filter: {:?}", args.pair_filter);
+        eprintln!("pair minimum overlap: {}", args.pair_min_overlap);
+        eprintln!("poly-x trim: {:?}", poly_x);
         eprintln!("compress output: {}", args.zip);
         eprintln!("threads requested: {}", args.threads);
         eprintln!("detected cores: {}", num_cpus::get());
@@ -190,31 +285,91 @@ fn main() -> Result<(), Box<dyn Error>> {
         .build_global()
         .unwrap();
 
-    use adapto_rs::remove_adaptors;
+    use adapto_rs::{remove_adaptors, remove_adaptors_paired};
 
-    if let (Some(pfastq), Some(pout)) = (args.pfastq, args.pout) {
-        remove_adaptors(
+    let report = if let (Some(pfastq), Some(pout)) = (args.pfastq, args.pout) {
+        remove_adaptors_paired(
             args.zip,
             args.threads,
             args.buffer_size,
-            &adaptor,
+            &adaptors,
+            &args.fastq,
             &pfastq,
+            &args.out,
             &pout,
             args.qual_cutoff,
             args.min_match_frac,
             args.min_overlap,
-        )?;
+            args.indel,
+            poly_x,
+            args.min_length,
+            args.on_short,
+            args.pair_filter,
+            args.pair_min_overlap,
+        )?
+    } else {
+        remove_adaptors(
+            args.zip,
+            args.threads,
+            args.buffer_size,
+            &adaptors,
+            &args.fastq,
+            &args.out,
+            args.qual_cutoff,
+            args.min_match_frac,
+            args.min_overlap,
+            args.indel,
+            poly_x,
+            args.min_length,
+            args.on_short,
+        )?
+    };
+
+    if let Some(ref report_path) = args.report {
+        write_report(report_path, &report, &adaptors)?;
     }
 
-    remove_adaptors(
-        args.zip,
-        args.threads,
-        args.buffer_size,
-        &adaptor,
-        &args.fastq,
-        &args.out,
-        args.qual_cutoff,
-        args.min_match_frac,
-        args.min_overlap,
-    )
+    Ok(())
+}
+
+/// Render a `TrimReport` as JSON, pairing each `adaptor_matches`
+/// count back up with the adaptor it belongs to, and write it to
+/// `path`, or to stderr if `path` is "-".
+fn write_report(
+    path: &str,
+    report: &adapto_rs::TrimReport,
+    adaptors: &[Adaptor],
+) -> Result<(), Box<dyn Error>> {
+    let adaptor_matches: Vec<_> = adaptors
+        .iter()
+        .zip(report.adaptor_matches.iter())
+        .map(|(a, &matches)| {
+            serde_json::json!({
+                "seq": String::from_utf8_lossy(&a.seq),
+                "end": format!("{:?}", a.end),
+                "matches": matches,
+            })
+        })
+        .collect();
+
+    let out = serde_json::json!({
+        "total_reads": report.total_reads,
+        "reads_with_adaptor": report.reads_with_adaptor,
+        "qual_bases_removed": report.qual_bases_removed,
+        "n_bases_removed": report.n_bases_removed,
+        "adaptor_bases_removed": report.adaptor_bases_removed,
+        "poly_x_bases_removed": report.poly_x_bases_removed,
+        "read_through_bases_removed": report.read_through_bases_removed,
+        "length_hist_before": report.length_hist_before,
+        "length_hist_after": report.length_hist_after,
+        "adaptor_matches": adaptor_matches,
+    });
+    let text = serde_json::to_string_pretty(&out)?;
+
+    if path == "-" {
+        eprintln!("{text}");
+    } else {
+        fs::write(path, text)?;
+    }
+    Ok(())
 }